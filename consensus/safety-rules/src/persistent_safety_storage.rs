@@ -14,15 +14,257 @@ use libra_secure_storage::{
     CachedStorage, CryptoStorage, InMemoryStorage, KVStorage, Storage, Value,
 };
 use libra_types::waypoint::Waypoint;
-use std::str::FromStr;
+use serde::Deserialize;
+use std::{
+    cell::RefCell, collections::HashMap, convert::TryFrom, marker::PhantomData, path::Path,
+    path::PathBuf, str::FromStr,
+};
+
+/// Key under which the on-disk schema version is stored. This is local to safety-rules, so it
+/// lives here rather than in `libra_global_constants`.
+const SCHEMA_VERSION: &str = "safety_rules_schema_version";
+
+/// The current on-disk layout version. Bump this and append a `migrate_vN_to_vN+1` step to
+/// `MIGRATIONS` whenever the persisted key set or the shape of any persisted value changes.
+const CURRENT_SCHEMA_VERSION: u64 = 1;
+
+type Migration = fn(&mut Storage) -> Result<()>;
+
+/// Ordered upgrade chain: `MIGRATIONS[i]` transforms a store from version `i` to version `i + 1`.
+/// Keep this append-only and each step idempotent, so a crash mid-upgrade can be safely retried.
+const MIGRATIONS: &[Migration] = &[migrate_v0_to_v1];
+
+/// Stores created before schema versioning existed are treated as version 0. The key layout is
+/// unchanged between version 0 and 1, so this step only exists to stamp a version onto them.
+fn migrate_v0_to_v1(_internal_store: &mut Storage) -> Result<()> {
+    Ok(())
+}
+
+/// Reads the stored schema version (defaulting to 0 for stores predating versioning) and runs
+/// any outstanding migrations, bumping the stored version after each step succeeds. Errors out
+/// rather than proceeding if the store claims a version newer than this binary understands.
+fn migrate(internal_store: &mut Storage) -> Result<()> {
+    let mut version = match internal_store.get(SCHEMA_VERSION) {
+        Ok(response) => response.value.u64()?,
+        Err(libra_secure_storage::Error::KeyNotSet(_)) => 0,
+        Err(e) => return Err(e.into()),
+    };
+
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(anyhow::anyhow!(
+            "Persistent storage schema version {} is newer than the version {} this binary understands",
+            version,
+            CURRENT_SCHEMA_VERSION,
+        ));
+    }
+
+    while version < CURRENT_SCHEMA_VERSION {
+        MIGRATIONS[version as usize](internal_store)?;
+        version += 1;
+        internal_store.set(SCHEMA_VERSION, Value::U64(version))?;
+    }
+    Ok(())
+}
+
+/// A batch of writes against the backing `Storage`, staged until `commit`, with best-effort
+/// rollback if a write fails partway through. This is deliberately NOT named or documented as
+/// atomic: `Storage` has no multi-key write primitive, so each staged key is still flushed via
+/// its own `set` round trip, and a process crash between two of those writes (as opposed to a
+/// `set` call returning an `Err`) can still leave storage partially updated. Closing that gap for
+/// real would mean adding a multi-key write primitive to `Storage` itself; until then, this only
+/// protects against synchronous write failures, not crashes.
+struct RollbackBatch<'a> {
+    internal_store: &'a mut Storage,
+    originals: HashMap<&'static str, Option<Value>>,
+    overlay: Vec<(&'static str, Value)>,
+}
+
+impl<'a> RollbackBatch<'a> {
+    fn open(internal_store: &'a mut Storage) -> Self {
+        Self {
+            internal_store,
+            originals: HashMap::new(),
+            overlay: Vec::new(),
+        }
+    }
+
+    /// Captures the key's pre-transaction value the first time it's touched, and stages `value`
+    /// to be written on `commit`.
+    fn stage(&mut self, key: &'static str, value: Value) -> Result<()> {
+        if !self.originals.contains_key(key) {
+            let original = match self.internal_store.get(key) {
+                Ok(response) => Some(response.value),
+                Err(libra_secure_storage::Error::KeyNotSet(_)) => None,
+                Err(e) => return Err(e.into()),
+            };
+            self.originals.insert(key, original);
+        }
+        self.overlay.push((key, value));
+        Ok(())
+    }
+
+    /// Flushes every staged write to the backend, rolling back on the first failure.
+    fn commit(mut self) -> Result<()> {
+        for (key, value) in self.overlay.clone() {
+            if let Err(e) = self.internal_store.set(key, value) {
+                self.revert();
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Restores every key this checkpoint touched to its pre-transaction value. Keys that were
+    /// previously absent are left as-is, since this storage interface has no delete.
+    fn revert(&mut self) {
+        for (key, original) in &self.originals {
+            if let Some(value) = original {
+                if let Err(e) = self.internal_store.set(key, value.clone()) {
+                    error!(
+                        "Failed to roll back key {} during transaction revert: {}",
+                        key, e
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Abstracts over the asymmetric signature scheme backing safety-rules' persisted keys, so
+/// `PersistentSafetyStorage` isn't hard-wired to Ed25519.
+pub trait SafetyKeyScheme {
+    type PrivateKey;
+    type PublicKey: Clone;
+
+    /// Imports `key` into `internal_store` under `name` as the current version.
+    fn import(internal_store: &mut Storage, name: &str, key: Self::PrivateKey) -> Result<()>;
+
+    /// Returns the private key for the version that produced `public_key`.
+    fn export_for_version(
+        internal_store: &Storage,
+        name: &str,
+        public_key: Self::PublicKey,
+    ) -> Result<Self::PrivateKey>;
+
+    /// Returns the current public key stored under `name`.
+    fn public_key(internal_store: &Storage, name: &str) -> Result<Self::PublicKey>;
+
+    /// Returns the public key of the version superseded by the current one under `name`.
+    fn previous_public_key(internal_store: &Storage, name: &str) -> Result<Self::PublicKey>;
+
+    /// Generates a fresh keypair under `name`, retaining the previous version, and returns the
+    /// new public key.
+    fn rotate(internal_store: &mut Storage, name: &str) -> Result<Self::PublicKey>;
+}
+
+/// The default signature scheme, backed directly by `CryptoStorage`.
+pub struct Ed25519Scheme;
+
+impl SafetyKeyScheme for Ed25519Scheme {
+    type PrivateKey = Ed25519PrivateKey;
+    type PublicKey = Ed25519PublicKey;
+
+    fn import(internal_store: &mut Storage, name: &str, key: Self::PrivateKey) -> Result<()> {
+        internal_store
+            .import_private_key(name, key)
+            .map_err(|e| e.into())
+    }
+
+    fn export_for_version(
+        internal_store: &Storage,
+        name: &str,
+        public_key: Self::PublicKey,
+    ) -> Result<Self::PrivateKey> {
+        internal_store
+            .export_private_key_for_version(name, public_key)
+            .map_err(|e| e.into())
+    }
+
+    fn public_key(internal_store: &Storage, name: &str) -> Result<Self::PublicKey> {
+        internal_store
+            .get_public_key(name)
+            .map(|r| r.public_key)
+            .map_err(|e| e.into())
+    }
+
+    fn previous_public_key(internal_store: &Storage, name: &str) -> Result<Self::PublicKey> {
+        internal_store
+            .get_public_key_previous_version(name)
+            .map_err(|e| e.into())
+    }
+
+    fn rotate(internal_store: &mut Storage, name: &str) -> Result<Self::PublicKey> {
+        internal_store.rotate_key(name).map_err(|e| e.into())
+    }
+}
+
+/// Where a validator definition's key material comes from. `resolve` returns `None` for
+/// `SecretsManager`, whose keys are picked up via `auto_discover` instead of being imported.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    /// The private key, hex-encoded, directly in the definitions file.
+    Inline(String),
+    /// A path to a file holding the hex-encoded private key.
+    KeystorePath(PathBuf),
+    /// An opaque reference to a key held by an external secrets manager; not resolvable here.
+    SecretsManager(String),
+}
+
+impl KeySource {
+    fn resolve(&self) -> Result<Option<Ed25519PrivateKey>> {
+        let encoded = match self {
+            KeySource::Inline(key) => key.clone(),
+            KeySource::KeystorePath(path) => std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Unable to read {}: {}", path.display(), e))?
+                .trim()
+                .to_string(),
+            KeySource::SecretsManager(_) => return Ok(None),
+        };
+        let bytes = hex::decode(encoded.trim())?;
+        Ok(Some(Ed25519PrivateKey::try_from(bytes.as_slice())?))
+    }
+}
+
+/// One validator's entry in a `ValidatorDefinitions` file.
+#[derive(Debug, Deserialize)]
+pub struct ValidatorDefinition {
+    /// The validator's owner/author account, as a hex-encoded `AccountAddress`.
+    pub author: String,
+    pub consensus_key: KeySource,
+    pub execution_key: KeySource,
+    /// The starting waypoint. Defaults to `Waypoint::default()` if omitted.
+    #[serde(default)]
+    pub waypoint: Option<String>,
+}
+
+/// The shape of a declarative validator-definitions file consumed by
+/// `PersistentSafetyStorage::from_validator_definitions`. Exactly one `validators` entry is
+/// required; this type doesn't yet support bootstrapping more than one validator per file.
+#[derive(Debug, Deserialize)]
+pub struct ValidatorDefinitions {
+    pub validators: Vec<ValidatorDefinition>,
+    /// If set, keys are assumed to already be present in the backend (e.g. provisioned by a
+    /// secrets manager out-of-band) and are verified rather than imported.
+    #[serde(default)]
+    pub auto_discover: bool,
+}
 
 /// SafetyRules needs an abstract storage interface to act as a common utility for storing
 /// persistent data to local disk, cloud, secrets managers, or even memory (for tests)
 /// Any set function is expected to sync to the remote system before returning.
 /// @TODO add access to private key from persistent store
-/// @TODO add retrieval of private key based upon public key to persistent store
-pub struct PersistentSafetyStorage {
+pub struct PersistentSafetyStorage<S: SafetyKeyScheme = Ed25519Scheme> {
     internal_store: Storage,
+    // Caches the current public key by key identifier (e.g. `CONSENSUS_KEY`) to spare the hot
+    // signature-verification path a storage round-trip. Populated lazily, kept in sync on
+    // rotation.
+    public_key_cache: RefCell<HashMap<&'static str, S::PublicKey>>,
+    // Same idea, one version behind: the public key superseded by the current one, as returned by
+    // `list_consensus_key_versions`. Private keys (`consensus_key_for_version`) are deliberately
+    // never cached here or anywhere else, so key material doesn't outlive the call that needed it.
+    previous_public_key_cache: RefCell<HashMap<&'static str, S::PublicKey>>,
+    _scheme: PhantomData<S>,
 }
 
 impl PersistentSafetyStorage {
@@ -43,11 +285,111 @@ impl PersistentSafetyStorage {
     /// Use this to instantiate a PersistentStorage for a new data store, one that has no
     /// SafetyRules values set.
     pub fn initialize(
-        mut internal_store: Storage,
+        internal_store: Storage,
         author: Author,
         consensus_private_key: Ed25519PrivateKey,
         execution_private_key: Ed25519PrivateKey,
         waypoint: Waypoint,
+    ) -> Self {
+        Self::initialize_with_scheme(
+            internal_store,
+            author,
+            consensus_private_key,
+            execution_private_key,
+            waypoint,
+        )
+    }
+
+    /// Use this to instantiate a PersistentStorage with an existing data store. This is intended
+    /// for constructed environments.
+    pub fn new(internal_store: Storage) -> Self {
+        Self::new_with_scheme(internal_store)
+    }
+
+    /// Reads a declarative validator-definitions file (YAML) and initializes `internal_store`
+    /// from it. See `ValidatorDefinitions` for the file's shape.
+    pub fn from_validator_definitions(
+        internal_store: Storage,
+        definitions_path: &Path,
+    ) -> Result<Self> {
+        let contents = std::fs::read_to_string(definitions_path)
+            .map_err(|e| anyhow::anyhow!("Unable to read {}: {}", definitions_path.display(), e))?;
+        let definitions: ValidatorDefinitions = serde_yaml::from_str(&contents)?;
+        Self::from_definitions(internal_store, definitions)
+    }
+
+    fn from_definitions(
+        internal_store: Storage,
+        definitions: ValidatorDefinitions,
+    ) -> Result<Self> {
+        if definitions.validators.len() != 1 {
+            return Err(anyhow::anyhow!(
+                "Validator definitions file must contain exactly one validator, found {}",
+                definitions.validators.len()
+            ));
+        }
+        let definition = definitions.validators.into_iter().next().unwrap();
+
+        let author = Author::from_str(&definition.author)
+            .map_err(|e| anyhow::anyhow!("Invalid author in validator definitions: {}", e))?;
+        let waypoint = match definition.waypoint {
+            Some(waypoint) => Waypoint::from_str(&waypoint)
+                .map_err(|e| anyhow::anyhow!("Invalid waypoint in validator definitions: {}", e))?,
+            None => Waypoint::default(),
+        };
+
+        if definitions.auto_discover {
+            // auto_discover skips key import entirely: the backend is expected to already hold
+            // the consensus and execution keys (e.g. provisioned out-of-band by a secrets
+            // manager), and we only need to confirm that's actually true before recording the
+            // bootstrap-only fields.
+            Ed25519Scheme::public_key(&internal_store, CONSENSUS_KEY).map_err(|_| {
+                anyhow::anyhow!("auto_discover is set but no consensus key was found in storage")
+            })?;
+            Ed25519Scheme::public_key(&internal_store, EXECUTION_KEY).map_err(|_| {
+                anyhow::anyhow!("auto_discover is set but no execution key was found in storage")
+            })?;
+            return Self::new_with_discovered_account(internal_store, author, waypoint);
+        }
+
+        let consensus_private_key = definition.consensus_key.resolve()?.ok_or_else(|| {
+            anyhow::anyhow!("consensus_key must be importable (inline or keystore_path) unless auto_discover is set")
+        })?;
+        let execution_private_key = definition.execution_key.resolve()?.ok_or_else(|| {
+            anyhow::anyhow!("execution_key must be importable (inline or keystore_path) unless auto_discover is set")
+        })?;
+
+        Ok(Self::initialize(
+            internal_store,
+            author,
+            consensus_private_key,
+            execution_private_key,
+            waypoint,
+        ))
+    }
+
+    /// Stamps `author` and `waypoint` onto a store whose keys are assumed to already be present
+    /// (the `auto_discover` path of `from_validator_definitions`).
+    fn new_with_discovered_account(
+        mut internal_store: Storage,
+        author: Author,
+        waypoint: Waypoint,
+    ) -> Result<Self> {
+        internal_store.set(OWNER_ACCOUNT, Value::String(author.to_string()))?;
+        internal_store.set(WAYPOINT, Value::String(waypoint.to_string()))?;
+        Ok(Self::new(internal_store))
+    }
+}
+
+impl<S: SafetyKeyScheme> PersistentSafetyStorage<S> {
+    /// Scheme-agnostic counterpart to `PersistentSafetyStorage::initialize`, for backends other
+    /// than the default Ed25519 one (see `SafetyKeyScheme`).
+    pub fn initialize_with_scheme(
+        mut internal_store: Storage,
+        author: Author,
+        consensus_private_key: S::PrivateKey,
+        execution_private_key: S::PrivateKey,
+        waypoint: Waypoint,
     ) -> Self {
         Self::initialize_(
             &mut internal_store,
@@ -57,54 +399,80 @@ impl PersistentSafetyStorage {
             waypoint,
         )
         .expect("Unable to initialize backend storage");
-        Self { internal_store }
+        migrate(&mut internal_store).expect("Unable to migrate backend storage schema");
+        Self {
+            internal_store,
+            public_key_cache: RefCell::new(HashMap::new()),
+            previous_public_key_cache: RefCell::new(HashMap::new()),
+            _scheme: PhantomData,
+        }
     }
 
     fn initialize_(
         internal_store: &mut Storage,
         author: Author,
-        consensus_private_key: Ed25519PrivateKey,
-        execution_private_key: Ed25519PrivateKey,
+        consensus_private_key: S::PrivateKey,
+        execution_private_key: S::PrivateKey,
         waypoint: Waypoint,
     ) -> Result<()> {
-        let result = internal_store.import_private_key(CONSENSUS_KEY, consensus_private_key);
+        let result = S::import(internal_store, CONSENSUS_KEY, consensus_private_key);
         // Attempting to re-initialize existing storage. This can happen in environments like
         // cluster test. Rather than be rigid here, leave it up to the developer to detect
         // inconsistencies or why they did not reset storage between rounds. Do not repeat the
         // checks again below, because it is just too strange to have a partially configured
-        // storage.
-        if let Err(libra_secure_storage::Error::KeyAlreadyExists(_)) = result {
-            warn!("Attempted to re-initialize existing storage");
-            return Ok(());
+        // storage. Note we deliberately skip stamping SCHEMA_VERSION here: the store already has
+        // one (or none, if it predates versioning), and `migrate` is responsible for bringing it
+        // up to date.
+        if let Err(e) = &result {
+            if let Some(libra_secure_storage::Error::KeyAlreadyExists(_)) =
+                e.downcast_ref::<libra_secure_storage::Error>()
+            {
+                warn!("Attempted to re-initialize existing storage");
+                return Ok(());
+            }
         }
+        result?;
 
-        internal_store.import_private_key(EXECUTION_KEY, execution_private_key)?;
+        S::import(internal_store, EXECUTION_KEY, execution_private_key)?;
         internal_store.set(
             SAFETY_DATA,
             Value::SafetyData(SafetyData::new(1, 0, 0, None)),
         )?;
         internal_store.set(OWNER_ACCOUNT, Value::String(author.to_string()))?;
         internal_store.set(WAYPOINT, Value::String(waypoint.to_string()))?;
+        internal_store.set(SCHEMA_VERSION, Value::U64(CURRENT_SCHEMA_VERSION))?;
         Ok(())
     }
 
-    pub fn into_cached(self) -> PersistentSafetyStorage {
+    pub fn into_cached(self) -> PersistentSafetyStorage<S> {
         // will be an idempotent operation if the underlying storage is already a CachedStorage
         if let Storage::CachedStorage(cached_storage) = self.internal_store {
             PersistentSafetyStorage {
                 internal_store: Storage::CachedStorage(cached_storage),
+                public_key_cache: RefCell::new(HashMap::new()),
+                previous_public_key_cache: RefCell::new(HashMap::new()),
+                _scheme: PhantomData,
             }
         } else {
             PersistentSafetyStorage {
                 internal_store: Storage::CachedStorage(CachedStorage::new(self.internal_store)),
+                public_key_cache: RefCell::new(HashMap::new()),
+                previous_public_key_cache: RefCell::new(HashMap::new()),
+                _scheme: PhantomData,
             }
         }
     }
 
-    /// Use this to instantiate a PersistentStorage with an existing data store. This is intended
-    /// for constructed environments.
-    pub fn new(internal_store: Storage) -> Self {
-        Self { internal_store }
+    /// Scheme-agnostic counterpart to `PersistentSafetyStorage::new`, for backends other than
+    /// the default Ed25519 one (see `SafetyKeyScheme`).
+    pub fn new_with_scheme(mut internal_store: Storage) -> Self {
+        migrate(&mut internal_store).expect("Unable to migrate backend storage schema");
+        Self {
+            internal_store,
+            public_key_cache: RefCell::new(HashMap::new()),
+            previous_public_key_cache: RefCell::new(HashMap::new()),
+            _scheme: PhantomData,
+        }
     }
 
     pub fn author(&self) -> Result<Author> {
@@ -113,20 +481,77 @@ impl PersistentSafetyStorage {
         std::str::FromStr::from_str(&res)
     }
 
-    pub fn consensus_key_for_version(
-        &self,
-        version: Ed25519PublicKey,
-    ) -> Result<Ed25519PrivateKey> {
-        self.internal_store
-            .export_private_key_for_version(CONSENSUS_KEY, version)
-            .map_err(|e| e.into())
+    /// Exports the private key for `version`. Deliberately uncached, unlike the public-key
+    /// lookups below: caching private key material would keep it resident in memory longer than
+    /// the single call that needs it.
+    pub fn consensus_key_for_version(&self, version: S::PublicKey) -> Result<S::PrivateKey> {
+        S::export_for_version(&self.internal_store, CONSENSUS_KEY, version)
     }
 
-    pub fn execution_public_key(&self) -> Result<Ed25519PublicKey> {
-        Ok(self
-            .internal_store
-            .get_public_key(EXECUTION_KEY)
-            .map(|r| r.public_key)?)
+    pub fn consensus_public_key(&self) -> Result<S::PublicKey> {
+        self.cached_public_key(CONSENSUS_KEY)
+    }
+
+    /// Generates a fresh consensus keypair and makes it the current signing key, retaining the
+    /// previous version so `consensus_key_for_version` keeps working for in-flight rounds.
+    pub fn rotate_consensus_key(&mut self) -> Result<S::PublicKey> {
+        let previous_current = self.public_key_cache.borrow().get(CONSENSUS_KEY).cloned();
+        let new_key = S::rotate(&mut self.internal_store, CONSENSUS_KEY)?;
+        self.public_key_cache
+            .borrow_mut()
+            .insert(CONSENSUS_KEY, new_key.clone());
+        match previous_current {
+            Some(previous) => {
+                self.previous_public_key_cache
+                    .borrow_mut()
+                    .insert(CONSENSUS_KEY, previous);
+            }
+            None => {
+                self.previous_public_key_cache
+                    .borrow_mut()
+                    .remove(CONSENSUS_KEY);
+            }
+        }
+        Ok(new_key)
+    }
+
+    /// Returns the public key stored under `name`, reading through to the backend only on the
+    /// first call; subsequent calls are served from `public_key_cache`.
+    fn cached_public_key(&self, name: &'static str) -> Result<S::PublicKey> {
+        if let Some(key) = self.public_key_cache.borrow().get(name) {
+            return Ok(key.clone());
+        }
+        let key = S::public_key(&self.internal_store, name)?;
+        self.public_key_cache.borrow_mut().insert(name, key.clone());
+        Ok(key)
+    }
+
+    /// Returns the public key superseded by the current one under `name`, reading through to the
+    /// backend only on the first call; subsequent calls are served from
+    /// `previous_public_key_cache`.
+    fn cached_previous_public_key(&self, name: &'static str) -> Result<S::PublicKey> {
+        if let Some(key) = self.previous_public_key_cache.borrow().get(name) {
+            return Ok(key.clone());
+        }
+        let key = S::previous_public_key(&self.internal_store, name)?;
+        self.previous_public_key_cache
+            .borrow_mut()
+            .insert(name, key.clone());
+        Ok(key)
+    }
+
+    /// Returns the current consensus public key, followed by the key it superseded if the backend
+    /// still has one. Versions older than that are not listed.
+    pub fn list_consensus_key_versions(&self) -> Result<Vec<S::PublicKey>> {
+        let mut versions = vec![self.consensus_public_key()?];
+        if let Ok(previous) = self.cached_previous_public_key(CONSENSUS_KEY) {
+            versions.push(previous);
+        }
+        Ok(versions)
+    }
+
+    pub fn execution_public_key(&self) -> Result<S::PublicKey> {
+        self.cached_public_key(EXECUTION_KEY)
     }
 
     pub fn safety_data(&self) -> Result<SafetyData> {
@@ -145,6 +570,28 @@ impl PersistentSafetyStorage {
         Ok(())
     }
 
+    /// Updates SafetyData (epoch, last_voted_round, preferred_round) and the waypoint via a
+    /// `RollbackBatch`, rolling both back if either write fails. This is best-effort, not atomic:
+    /// see `RollbackBatch` for why a crash between the two underlying `set` calls (as opposed to
+    /// one returning an `Err`) can still leave storage partially updated.
+    pub fn set_safety_data_and_waypoint(
+        &mut self,
+        data: SafetyData,
+        waypoint: &Waypoint,
+    ) -> Result<()> {
+        let mut checkpoint = RollbackBatch::open(&mut self.internal_store);
+        checkpoint.stage(SAFETY_DATA, Value::SafetyData(data.clone()))?;
+        checkpoint.stage(WAYPOINT, Value::String(waypoint.to_string()))?;
+        checkpoint.commit()?;
+
+        counters::set_state("epoch", data.epoch as i64);
+        counters::set_state("last_voted_round", data.last_voted_round as i64);
+        counters::set_state("preferred_round", data.preferred_round as i64);
+        send_struct_log!(logging::safety_log(LogEntry::Waypoint, LogEvent::Update)
+            .data(LogField::Message.as_str(), waypoint));
+        Ok(())
+    }
+
     pub fn waypoint(&self) -> Result<Waypoint> {
         let waypoint = self
             .internal_store
@@ -193,4 +640,208 @@ mod tests {
         assert_eq!(safety_data.last_voted_round, 8);
         assert_eq!(safety_data.preferred_round, 1);
     }
+
+    #[test]
+    fn migrate_rejects_future_schema_version() {
+        let mut storage = Storage::from(InMemoryStorage::new());
+        storage
+            .set(SCHEMA_VERSION, Value::U64(CURRENT_SCHEMA_VERSION + 1))
+            .unwrap();
+        assert!(migrate(&mut storage).is_err());
+    }
+
+    #[test]
+    fn checkpoint_revert_restores_original_value() {
+        let mut storage = Storage::from(InMemoryStorage::new());
+        storage
+            .set(WAYPOINT, Value::String("original".into()))
+            .unwrap();
+
+        let mut checkpoint = RollbackBatch::open(&mut storage);
+        checkpoint
+            .stage(WAYPOINT, Value::String("staged".into()))
+            .unwrap();
+        checkpoint.revert();
+
+        assert_eq!(
+            storage.get(WAYPOINT).unwrap().value.string().unwrap(),
+            "original"
+        );
+    }
+
+    #[test]
+    fn rotate_consensus_key_retains_previous_version() {
+        let mut storage = PersistentSafetyStorage::in_memory(
+            Ed25519PrivateKey::generate_for_testing(),
+            Ed25519PrivateKey::generate_for_testing(),
+        );
+        let original_public_key = storage.consensus_public_key().unwrap();
+        let original_private_key = storage
+            .consensus_key_for_version(original_public_key.clone())
+            .unwrap();
+
+        let new_public_key = storage.rotate_consensus_key().unwrap();
+        assert_ne!(new_public_key, original_public_key);
+        assert_eq!(storage.consensus_public_key().unwrap(), new_public_key);
+        assert_eq!(
+            storage
+                .consensus_key_for_version(original_public_key)
+                .unwrap(),
+            original_private_key
+        );
+    }
+
+    #[test]
+    fn initialize_with_scheme_accepts_an_explicit_scheme() {
+        let storage = Storage::from(InMemoryStorage::new());
+        let storage = PersistentSafetyStorage::<Ed25519Scheme>::initialize_with_scheme(
+            storage,
+            Author::random(),
+            Ed25519PrivateKey::generate_for_testing(),
+            Ed25519PrivateKey::generate_for_testing(),
+            Waypoint::default(),
+        );
+        assert_eq!(storage.safety_data().unwrap().epoch, 1);
+    }
+
+    /// A second `SafetyKeyScheme` implementor, used only to prove `PersistentSafetyStorage<S>`
+    /// doesn't secretly assume the real `CryptoStorage` API underneath. Unlike `Ed25519Scheme` it
+    /// goes through bare `KVStorage`, storing keys as plain `Value::U64`.
+    struct MockScheme;
+
+    impl MockScheme {
+        fn previous_key(name: &str) -> String {
+            format!("{}_previous", name)
+        }
+    }
+
+    impl SafetyKeyScheme for MockScheme {
+        type PrivateKey = u64;
+        type PublicKey = u64;
+
+        fn import(internal_store: &mut Storage, name: &str, key: Self::PrivateKey) -> Result<()> {
+            internal_store.set(name, Value::U64(key))?;
+            Ok(())
+        }
+
+        fn export_for_version(
+            internal_store: &Storage,
+            name: &str,
+            public_key: Self::PublicKey,
+        ) -> Result<Self::PrivateKey> {
+            if internal_store.get(name)?.value.u64()? == public_key {
+                return Ok(public_key);
+            }
+            if internal_store.get(&Self::previous_key(name))?.value.u64()? == public_key {
+                return Ok(public_key);
+            }
+            Err(anyhow::anyhow!("No key found for version {}", public_key))
+        }
+
+        fn public_key(internal_store: &Storage, name: &str) -> Result<Self::PublicKey> {
+            Ok(internal_store.get(name)?.value.u64()?)
+        }
+
+        fn previous_public_key(internal_store: &Storage, name: &str) -> Result<Self::PublicKey> {
+            Ok(internal_store.get(&Self::previous_key(name))?.value.u64()?)
+        }
+
+        fn rotate(internal_store: &mut Storage, name: &str) -> Result<Self::PublicKey> {
+            let current = internal_store.get(name)?.value.u64()?;
+            internal_store.set(&Self::previous_key(name), Value::U64(current))?;
+            let new_key = current + 1;
+            internal_store.set(name, Value::U64(new_key))?;
+            Ok(new_key)
+        }
+    }
+
+    #[test]
+    fn mock_scheme_exercises_the_full_safety_key_scheme_api() {
+        let storage = Storage::from(InMemoryStorage::new());
+        let mut storage = PersistentSafetyStorage::<MockScheme>::initialize_with_scheme(
+            storage,
+            Author::random(),
+            1,
+            2,
+            Waypoint::default(),
+        );
+
+        assert_eq!(storage.consensus_public_key().unwrap(), 1);
+        assert_eq!(storage.execution_public_key().unwrap(), 2);
+        assert_eq!(storage.consensus_key_for_version(1).unwrap(), 1);
+
+        let rotated = storage.rotate_consensus_key().unwrap();
+        assert_eq!(rotated, 2);
+        assert_eq!(storage.consensus_public_key().unwrap(), 2);
+        assert_eq!(storage.consensus_key_for_version(1).unwrap(), 1);
+        assert_eq!(storage.list_consensus_key_versions().unwrap(), vec![2, 1]);
+    }
+
+    #[test]
+    fn public_key_cache_is_invalidated_on_rotation() {
+        let mut storage = PersistentSafetyStorage::in_memory(
+            Ed25519PrivateKey::generate_for_testing(),
+            Ed25519PrivateKey::generate_for_testing(),
+        );
+        let cached_before_rotation = storage.consensus_public_key().unwrap();
+        let rotated = storage.rotate_consensus_key().unwrap();
+        assert_ne!(cached_before_rotation, rotated);
+        assert_eq!(storage.consensus_public_key().unwrap(), rotated);
+    }
+
+    #[test]
+    fn list_consensus_key_versions_tracks_rotation_through_the_cache() {
+        let mut storage = PersistentSafetyStorage::in_memory(
+            Ed25519PrivateKey::generate_for_testing(),
+            Ed25519PrivateKey::generate_for_testing(),
+        );
+        let original = storage.consensus_public_key().unwrap();
+        assert_eq!(
+            storage.list_consensus_key_versions().unwrap(),
+            vec![original.clone()]
+        );
+
+        let rotated = storage.rotate_consensus_key().unwrap();
+        assert_eq!(
+            storage.list_consensus_key_versions().unwrap(),
+            vec![rotated, original]
+        );
+    }
+
+    #[test]
+    fn from_definitions_constructs_a_store_from_inline_keys() {
+        let author = Author::random();
+        let consensus_key = hex::encode([42u8; 32]);
+        let execution_key = hex::encode([7u8; 32]);
+        let definitions = ValidatorDefinitions {
+            validators: vec![ValidatorDefinition {
+                author: author.to_string(),
+                consensus_key: KeySource::Inline(consensus_key),
+                execution_key: KeySource::Inline(execution_key),
+                waypoint: None,
+            }],
+            auto_discover: false,
+        };
+        let storage = Storage::from(InMemoryStorage::new());
+        let storage = PersistentSafetyStorage::from_definitions(storage, definitions).unwrap();
+
+        assert_eq!(storage.author().unwrap(), author);
+        assert_eq!(storage.waypoint().unwrap(), Waypoint::default());
+        assert_eq!(storage.safety_data().unwrap().epoch, 1);
+    }
+
+    #[test]
+    fn from_definitions_rejects_an_invalid_author_before_writing() {
+        let definitions = ValidatorDefinitions {
+            validators: vec![ValidatorDefinition {
+                author: "not-a-valid-author".to_string(),
+                consensus_key: KeySource::Inline("irrelevant".to_string()),
+                execution_key: KeySource::Inline("irrelevant".to_string()),
+                waypoint: None,
+            }],
+            auto_discover: false,
+        };
+        let storage = Storage::from(InMemoryStorage::new());
+        assert!(PersistentSafetyStorage::from_definitions(storage, definitions).is_err());
+    }
 }